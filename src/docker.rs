@@ -1,14 +1,39 @@
-use anyhow::{anyhow,Context,Result};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
 use semver::Version;
+use serde::Deserialize;
 use std::fs;
 use std::path::Path;
 
-#[derive(Debug)]
-pub struct Options {
-    pub skip_errors: bool,
-    pub dry_run: bool,
-    pub force: bool,
-    pub include_latest: bool,
+/// Which semver pre-release tags are eligible to sync.
+///
+/// `Stable` (the default) only considers tags whose version has an empty
+/// pre-release segment; `Prerelease` also opens up `-rc`/`-beta` tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReleaseTrack {
+    Stable,
+    Prerelease,
+}
+
+impl Default for ReleaseTrack {
+    fn default() -> Self {
+        ReleaseTrack::Stable
+    }
+}
+
+/// A tag fetched from the registry, along with the metadata needed to select it
+/// either by semver or, as a fallback, by timestamp.
+#[derive(Debug, Clone)]
+pub struct TagCandidate {
+    pub name: String,
+    pub last_updated: DateTime<Utc>,
+}
+
+/// Parses a tag name as a semver version, stripping a leading `v` (e.g. `v1.2.3`).
+fn parse_tag_version(tag_name: &str) -> Option<Version> {
+    let stripped = tag_name.strip_prefix('v').unwrap_or(tag_name);
+    Version::parse(stripped).ok()
 }
 
 #[derive(Debug)]
@@ -28,46 +53,184 @@ impl DockerImage {
         }
     }
 
-    fn latest_synced_image(&self, directory: &String, options: &Options) -> Result<String> {
-        let dir = Path::new(directory).join(self.repository.clone());
+    /// The highest semver version already present among the synced `.sif` files for
+    /// this image, or `None` if there are no synced files or none of them parse as
+    /// a version.
+    fn highest_synced_version(&self, directory: &String) -> Result<Option<Version>> {
+        let dir = Path::new(directory).join(&self.repository);
 
         if !dir.is_dir() {
-            if !options.force {
-                return Err(anyhow!("{:#?} is not a directory", dir));
-            }
-
-            fs::create_dir(&dir).context("Could not create directory")?;
+            return Ok(None);
         }
 
-        let latest = String::new();
-        let version = Version::parse("0.0.0");
-        for entry in fs::read_dir(&dir)? {
-            let entry = entry?;
-            let path = entry.path();
+        let highest = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().and_then(|ext| ext.to_str()) == Some("sif"))
+            .filter_map(|entry| {
+                entry
+                    .path()
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .map(String::from)
+            })
+            .filter_map(|stem| {
+                let tag = stem.strip_prefix(&format!("{}-", self.image))?;
+                parse_tag_version(tag)
+            })
+            .max();
+
+        Ok(highest)
+    }
+
+    /// Selects which of `candidates` should be synced: tags that parse as semver are
+    /// synced only if they are strictly newer than the highest version already
+    /// synced and match `track`, and are returned newest-version-first so a
+    /// first-sync truncation picks the newest N versions rather than whatever
+    /// order the registry happened to page them in. Tags that fail to parse fall
+    /// back to the existing `last_updated > latest_sync` behavior (newest first)
+    /// so non-versioned images keep working, and are appended after the
+    /// versioned ones.
+    pub fn select_tags(
+        &self,
+        directory: &String,
+        candidates: &[TagCandidate],
+        latest_sync: DateTime<Utc>,
+        track: ReleaseTrack,
+    ) -> Result<Vec<String>> {
+        let highest = self.highest_synced_version(directory)?;
+
+        let mut versioned: Vec<(Version, &str)> = Vec::new();
+        let mut unversioned: Vec<(DateTime<Utc>, &str)> = Vec::new();
 
-            println!("{:#?}", path);
+        for candidate in candidates {
+            match parse_tag_version(&candidate.name) {
+                Some(version) => {
+                    let prerelease_ok = track == ReleaseTrack::Prerelease || version.pre.is_empty();
+                    let newer = highest.as_ref().map_or(true, |h| &version > h);
+                    if prerelease_ok && newer {
+                        versioned.push((version, &candidate.name));
+                    }
+                }
+                None if candidate.last_updated > latest_sync => {
+                    unversioned.push((candidate.last_updated, &candidate.name));
+                }
+                None => {}
+            }
         }
 
-        Ok(latest)
-    }
+        versioned.sort_by(|a, b| b.0.cmp(&a.0));
+        unversioned.sort_by(|a, b| b.0.cmp(&a.0));
 
-    pub fn sync(&self, directory: &String, options: &Options) -> Result<()> {
-        self.latest_synced_image(directory, options)?;
-        Ok(())
+        Ok(versioned
+            .into_iter()
+            .map(|(_, name)| String::from(name))
+            .chain(unversioned.into_iter().map(|(_, name)| String::from(name)))
+            .collect())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs::{self, File};
 
     #[test]
     fn test_from() {
         let base = String::from("foo/bar");
         let docker_image = DockerImage::from(&base);
 
-        assert_eq!(docker_image.base, base);
         assert_eq!(docker_image.repository, String::from("foo"));
         assert_eq!(docker_image.image, String::from("bar"));
     }
+
+    /// Creates an empty scratch directory (with a `foo` repository subdirectory
+    /// already present) for a test to sync into, namespaced by test name and pid
+    /// so parallel test runs don't collide.
+    fn scratch_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!(
+            "singularity-sync-test-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("foo")).unwrap();
+        dir.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_select_tags_picks_highest_stable_versions_newest_first() {
+        let directory = scratch_dir("select-tags-semver");
+        File::create(Path::new(&directory).join("foo").join("bar-1.0.0.sif")).unwrap();
+
+        let image = DockerImage::from(&String::from("foo/bar"));
+        let epoch = DateTime::<Utc>::from(std::time::SystemTime::UNIX_EPOCH);
+        let candidates = vec![
+            TagCandidate {
+                name: String::from("1.1.0"),
+                last_updated: Utc::now(),
+            },
+            TagCandidate {
+                name: String::from("1.2.0"),
+                last_updated: Utc::now(),
+            },
+            TagCandidate {
+                name: String::from("2.0.0-rc1"),
+                last_updated: Utc::now(),
+            },
+            TagCandidate {
+                name: String::from("0.9.0"),
+                last_updated: Utc::now(),
+            },
+        ];
+
+        let selected = image
+            .select_tags(&directory, &candidates, epoch, ReleaseTrack::Stable)
+            .unwrap();
+
+        // 2.0.0-rc1 is excluded on the stable track, 0.9.0 is not newer than the
+        // already-synced 1.0.0, and the rest come back newest-version-first.
+        assert_eq!(selected, vec!["1.2.0".to_string(), "1.1.0".to_string()]);
+    }
+
+    #[test]
+    fn test_select_tags_includes_prerelease_on_prerelease_track() {
+        let directory = scratch_dir("select-tags-prerelease");
+
+        let image = DockerImage::from(&String::from("foo/bar"));
+        let epoch = DateTime::<Utc>::from(std::time::SystemTime::UNIX_EPOCH);
+        let candidates = vec![TagCandidate {
+            name: String::from("2.0.0-rc1"),
+            last_updated: Utc::now(),
+        }];
+
+        let selected = image
+            .select_tags(&directory, &candidates, epoch, ReleaseTrack::Prerelease)
+            .unwrap();
+
+        assert_eq!(selected, vec!["2.0.0-rc1".to_string()]);
+    }
+
+    #[test]
+    fn test_select_tags_falls_back_to_timestamp_for_non_semver_tags() {
+        let directory = scratch_dir("select-tags-fallback");
+
+        let image = DockerImage::from(&String::from("foo/baz"));
+        let latest_sync = Utc::now() - chrono::Duration::hours(1);
+        let candidates = vec![
+            TagCandidate {
+                name: String::from("nightly"),
+                last_updated: Utc::now(),
+            },
+            TagCandidate {
+                name: String::from("stale"),
+                last_updated: latest_sync - chrono::Duration::hours(1),
+            },
+        ];
+
+        let selected = image
+            .select_tags(&directory, &candidates, latest_sync, ReleaseTrack::Stable)
+            .unwrap();
+
+        assert_eq!(selected, vec!["nightly".to_string()]);
+    }
 }
@@ -1,6 +1,13 @@
+mod docker;
+mod runner;
+
 use anyhow::{anyhow, Context, Result};
 use chrono::{DateTime, Utc};
 use clap::{App, Arg};
+use docker::{DockerImage, ReleaseTrack, TagCandidate};
+use rayon::prelude::*;
+use regex::Regex;
+use runner::RetryPolicy;
 use serde::Deserialize;
 use std::fs::{self, File};
 use std::io::{self, Read};
@@ -13,11 +20,174 @@ struct Options {
     dry_run: bool,
     force: bool,
     first_sync: usize,
+    jobs: usize,
+    skip_errors: bool,
+    report_only: bool,
+    include_prerelease: bool,
+}
+
+/// A single `singularity build` invocation, fully resolved against one manifest entry.
+///
+/// Each job writes to its own `sif_path`, so jobs can be driven through a worker
+/// pool without any write contention.
+#[derive(Debug, Clone)]
+struct BuildJob {
+    repository: String,
+    image: String,
+    sif_path: String,
+    docker_uri: String,
+}
+
+/// The outcome of driving a single [`BuildJob`] through `build_job`.
+enum BuildStatus {
+    Built,
+    Skipped,
+    Failed(anyhow::Error),
+}
+
+/// Summarizes what happened for one manifest entry over the course of a run.
+struct ImageReport {
+    repository: String,
+    image: String,
+    latest_sync: DateTime<Utc>,
+    tags_found: usize,
+    built: usize,
+    skipped: usize,
+    failed: usize,
+    deferred: usize,
+}
+
+/// Renders a [`chrono::Duration`] as a human-readable age, collapsed to its
+/// largest non-zero unit (e.g. "3 Days", "1 Year"), singular vs plural.
+trait DisplayDurationExt {
+    fn to_display(&self) -> String;
+}
+
+impl DisplayDurationExt for chrono::Duration {
+    fn to_display(&self) -> String {
+        const MINUTE: i64 = 60;
+        const HOUR: i64 = 60 * MINUTE;
+        const DAY: i64 = 24 * HOUR;
+        const YEAR: i64 = 365 * DAY;
+
+        let seconds = self.num_seconds().max(0);
+
+        let (value, unit) = if seconds >= YEAR {
+            (seconds / YEAR, "Year")
+        } else if seconds >= DAY {
+            (seconds / DAY, "Day")
+        } else if seconds >= HOUR {
+            (seconds / HOUR, "Hour")
+        } else if seconds >= MINUTE {
+            (seconds / MINUTE, "Minute")
+        } else {
+            (seconds, "Second")
+        };
+
+        if value == 1 {
+            format!("{} {}", value, unit)
+        } else {
+            format!("{} {}s", value, unit)
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct Manifest {
-    docker: Vec<String>,
+    docker: Vec<ManifestEntry>,
+}
+
+/// One entry of `manifest.docker`: either a bare `"repository/image"` string
+/// (synced with the defaults), or an object spelling out a per-image policy.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum ManifestEntry {
+    Bare(String),
+    Config(ImageConfig),
+}
+
+impl ManifestEntry {
+    fn into_config(self) -> ImageConfig {
+        match self {
+            ManifestEntry::Bare(image) => ImageConfig {
+                image,
+                include: None,
+                exclude: Vec::new(),
+                first_sync: None,
+                track: None,
+            },
+            ManifestEntry::Config(config) => config,
+        }
+    }
+}
+
+/// Per-image sync policy. `include`/`exclude` are regex patterns matched against
+/// tag names; when neither is given, `exclude` falls back to the historical
+/// banned-substring list so bare manifest entries keep their old behavior.
+#[derive(Deserialize, Debug, Clone)]
+struct ImageConfig {
+    image: String,
+    #[serde(default)]
+    include: Option<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    #[serde(default)]
+    first_sync: Option<usize>,
+    #[serde(default)]
+    track: Option<ReleaseTrack>,
+}
+
+const DEFAULT_BANNED_TAGS: &[&str] = &["latest", "dev", "rc", "test", "unstable"];
+
+/// A compiled include/exclude regex matcher built from one image's [`ImageConfig`].
+struct TagMatcher {
+    include: Option<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl TagMatcher {
+    /// Builds a matcher from `config`'s include/exclude patterns. When neither is
+    /// set, falls back to the historical banned-substring list — except `rc`,
+    /// which is dropped from that default on the `Prerelease` track, so enabling
+    /// prereleases on a default-exclude image doesn't silently undo it.
+    fn from_config(config: &ImageConfig, track: ReleaseTrack) -> Result<TagMatcher> {
+        let include = config
+            .include
+            .as_ref()
+            .map(|pattern| Regex::new(pattern))
+            .transpose()
+            .context("Invalid include pattern")?;
+
+        let exclude = if config.exclude.is_empty() && config.include.is_none() {
+            DEFAULT_BANNED_TAGS
+                .iter()
+                .filter(|pattern| !(track == ReleaseTrack::Prerelease && **pattern == "rc"))
+                .map(|pattern| Regex::new(&regex::escape(pattern)).unwrap())
+                .collect()
+        } else {
+            config
+                .exclude
+                .iter()
+                .map(|pattern| Regex::new(pattern))
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .context("Invalid exclude pattern")?
+        };
+
+        Ok(TagMatcher { include, exclude })
+    }
+
+    fn is_allowed(&self, tag_name: &str) -> bool {
+        if let Some(include) = &self.include {
+            if !include.is_match(tag_name) {
+                return false;
+            }
+        }
+
+        !self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.is_match(tag_name))
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -134,24 +304,18 @@ fn lastest_sync_timestamp(dir: &Path, image: &str) -> Result<DateTime<Utc>> {
     Ok(DateTime::from(latest_sync))
 }
 
-fn is_banned_image(tag_name: &str, banned_tags: &[&str]) -> Result<bool> {
-    let chk = banned_tags.iter().any(|ban| tag_name.contains(ban));
-    Ok(chk)
-}
-
 fn tags_after_timestamp(
     repository: &str,
     image: &str,
     latest_sync: DateTime<Utc>,
-) -> Result<Vec<String>> {
+    matcher: &TagMatcher,
+) -> Result<Vec<TagCandidate>> {
     let mut url = format!(
         "https://registry.hub.docker.com/v2/repositories/{}/{}/tags",
         repository, image
     );
 
-    let banned_tags = vec!["latest", "dev", "rc", "test", "unstable"];
-
-    let mut tags: Vec<String> = Vec::new();
+    let mut tags: Vec<TagCandidate> = Vec::new();
 
     loop {
         let response = reqwest::blocking::get(&url)?;
@@ -159,8 +323,11 @@ fn tags_after_timestamp(
         let response: TagResponse = serde_json::from_str(&response)?;
 
         response.results.iter().for_each(|tag| {
-            if tag.last_updated > latest_sync && !is_banned_image(&tag.name, &banned_tags).unwrap() {
-                tags.push(tag.name.clone());
+            if tag.last_updated > latest_sync && matcher.is_allowed(&tag.name) {
+                tags.push(TagCandidate {
+                    name: tag.name.clone(),
+                    last_updated: tag.last_updated,
+                });
             }
         });
 
@@ -173,8 +340,17 @@ fn tags_after_timestamp(
     Ok(tags)
 }
 
-fn sync_docker_image(image: &str, directory: &str, options: &Options) -> Result<()> {
-    let image_split: Vec<&str> = image.rsplit('/').collect();
+/// Resolves the tags to sync for a single manifest entry into a list of [`BuildJob`]s.
+///
+/// This performs the Docker Hub tag-enumeration step, which has to stay sequential
+/// since `tags_after_timestamp` pages through the registry API and mutates its own
+/// pagination state as it goes. The resulting jobs are safe to build concurrently.
+fn discover_build_jobs(
+    config: &ImageConfig,
+    directory: &str,
+    options: &Options,
+) -> Result<(ImageReport, Vec<BuildJob>)> {
+    let image_split: Vec<&str> = config.image.rsplit('/').collect();
     let image = String::from(image_split[0]);
     let repository = String::from(image_split[1]);
     let image_dir = Path::new(directory).join(repository.clone());
@@ -188,43 +364,184 @@ fn sync_docker_image(image: &str, directory: &str, options: &Options) -> Result<
     }
 
     let latest_sync = lastest_sync_timestamp(&image_dir, &image)?;
-    let tags_to_sync = tags_after_timestamp(&repository, &image, latest_sync)?;
+
+    let default_track = if options.include_prerelease {
+        ReleaseTrack::Prerelease
+    } else {
+        ReleaseTrack::Stable
+    };
+    let track = config.track.unwrap_or(default_track);
+
+    let matcher = TagMatcher::from_config(config, track)?;
+    let candidates = tags_after_timestamp(&repository, &image, latest_sync, &matcher)?;
+
+    let docker_image = DockerImage::from(&format!("{}/{}", repository, image));
+    let tags_to_sync =
+        docker_image.select_tags(&directory.to_string(), &candidates, latest_sync, track)?;
+
+    let tags_found = tags_to_sync.len();
 
     let epoch: DateTime<Utc> = DateTime::from(SystemTime::UNIX_EPOCH);
+    let first_sync = config.first_sync.unwrap_or(options.first_sync);
     let tags_to_sync = if latest_sync == epoch {
-        &tags_to_sync[0..options.first_sync]
+        &tags_to_sync[0..first_sync.min(tags_to_sync.len())]
     } else {
         tags_to_sync.as_slice()
     };
 
-    for tag in tags_to_sync {
-        let sif_path = format!("{}/{}/{}-{}.sif", directory, repository, image, tag);
-        let docker_uri = format!("docker://{}/{}:{}", repository, image, tag);
+    let report = ImageReport {
+        repository: repository.clone(),
+        image: image.clone(),
+        latest_sync,
+        tags_found,
+        built: 0,
+        skipped: 0,
+        failed: 0,
+        deferred: tags_found - tags_to_sync.len(),
+    };
 
-        if options.dry_run {
-            let force = if options.force { "-F" } else { "" };
-            let sbatch_cmd = format!("singularity build {} {} {}", force, sif_path, docker_uri);
-            println!("{}", sbatch_cmd);
-        } else {
-            let mut command = Command::new("singularity");
+    let jobs = tags_to_sync
+        .iter()
+        .map(|tag| BuildJob {
+            repository: repository.clone(),
+            image: image.clone(),
+            sif_path: format!("{}/{}/{}-{}.sif", directory, repository, image, tag),
+            docker_uri: format!("docker://{}/{}:{}", repository, image, tag),
+        })
+        .collect();
 
-            command.arg("build");
+    Ok((report, jobs))
+}
 
-            if options.force {
-                command.arg("-F");
-            }
+/// Runs a single build job. Safe to call from multiple worker threads at once since
+/// every job targets a distinct `sif_path`.
+///
+/// A `singularity build` that exits non-zero is retried with backoff; if it still
+/// fails and `options.skip_errors` is set, the failure is logged as a warning and
+/// reported as skipped rather than aborting the whole sync.
+fn build_job(job: &BuildJob, options: &Options) -> BuildStatus {
+    if options.dry_run {
+        let force = if options.force { "-F" } else { "" };
+        let sbatch_cmd = format!(
+            "singularity build {} {} {}",
+            force, job.sif_path, job.docker_uri
+        );
+        println!("{}", sbatch_cmd);
+        return BuildStatus::Built;
+    }
+
+    let mut command = Command::new("singularity");
+
+    command.arg("build");
 
-            command.arg(sif_path).arg(docker_uri).status()?;
+    if options.force {
+        command.arg("-F");
+    }
+
+    command.arg(&job.sif_path).arg(&job.docker_uri);
+
+    match runner::run(&mut command, &RetryPolicy::default()) {
+        Ok(()) => BuildStatus::Built,
+        Err(err) if options.skip_errors => {
+            eprintln!(
+                "warning: skipping {} after build failure: {}",
+                job.docker_uri, err
+            );
+            BuildStatus::Skipped
         }
+        Err(err) => BuildStatus::Failed(err),
     }
+}
 
-    Ok(())
+/// Prints the end-of-run sync report: per image, the age of the most recent local
+/// `.sif`, how many new tags were found, and how many were built/skipped/failed.
+/// When builds run, `tags_found` reconciles as `built + skipped + failed + deferred`
+/// — tags held back by a `first_sync` cap are reported as deferred rather than
+/// silently dropped from the count. In `--report-only` mode nothing is built, so
+/// built/skipped/failed stay at zero.
+fn print_report(reports: &[ImageReport]) {
+    let epoch: DateTime<Utc> = DateTime::from(SystemTime::UNIX_EPOCH);
+
+    println!("Sync report:");
+    for report in reports {
+        let age = if report.latest_sync == epoch {
+            String::from("never synced")
+        } else {
+            format!("{} old", (Utc::now() - report.latest_sync).to_display())
+        };
+
+        println!(
+            "  {}/{}: {} ({} new, {} built, {} skipped, {} failed, {} deferred)",
+            report.repository,
+            report.image,
+            age,
+            report.tags_found,
+            report.built,
+            report.skipped,
+            report.failed,
+            report.deferred
+        );
+    }
 }
 
 fn sync_manifest(directory: &str, manifest: &Manifest, options: &Options) -> Result<()> {
-    for image in &manifest.docker {
-        sync_docker_image(image, directory, options)?;
+    let mut reports = Vec::new();
+    let mut jobs = Vec::new();
+    let mut seen_images = std::collections::HashSet::new();
+
+    for entry in &manifest.docker {
+        let config = entry.clone().into_config();
+
+        // Every job writes to a path derived from `repository/image`, so a manifest
+        // that lists the same image twice would hand two jobs the same `sif_path`
+        // and two reports the same (repository, image) key. Keep only the first
+        // occurrence.
+        if !seen_images.insert(config.image.clone()) {
+            eprintln!(
+                "warning: {} is listed more than once in the manifest, ignoring the duplicate",
+                config.image
+            );
+            continue;
+        }
+
+        let (report, discovered) = discover_build_jobs(&config, directory, options)?;
+        reports.push(report);
+        jobs.extend(discovered);
+    }
+
+    if !options.report_only {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(options.jobs)
+            .build()
+            .context("Could not build worker pool")?;
+
+        let outcomes: Vec<BuildStatus> =
+            pool.install(|| jobs.par_iter().map(|job| build_job(job, options)).collect());
+
+        for (job, status) in jobs.iter().zip(outcomes) {
+            let report = reports
+                .iter_mut()
+                .find(|r| r.repository == job.repository && r.image == job.image)
+                .expect("every build job was discovered from a report in this run");
+
+            match status {
+                BuildStatus::Built => report.built += 1,
+                BuildStatus::Skipped => report.skipped += 1,
+                BuildStatus::Failed(err) => {
+                    println!("  failed: {} ({})", job.docker_uri, err);
+                    report.failed += 1;
+                }
+            }
+        }
+    }
+
+    print_report(&reports);
+
+    let total_failed: usize = reports.iter().map(|r| r.failed).sum();
+    if total_failed > 0 {
+        return Err(anyhow!("{} build(s) failed", total_failed));
     }
+
     Ok(())
 }
 
@@ -265,6 +582,30 @@ fn main() -> Result<()> {
                 .default_value("5")
                 .help("The number of tags to pull on first sync"),
         )
+        .arg(
+            Arg::with_name("jobs")
+                .short("j")
+                .long("jobs")
+                .default_value("4")
+                .help("The number of images to build concurrently"),
+        )
+        .arg(
+            Arg::with_name("skip_errors")
+                .short("s")
+                .long("skip-errors")
+                .help("Log and continue past a tag that fails to build instead of aborting"),
+        )
+        .arg(
+            Arg::with_name("report_only")
+                .short("r")
+                .long("report-only")
+                .help("Print the sync report without building any images"),
+        )
+        .arg(
+            Arg::with_name("include_prerelease")
+                .long("include-prerelease")
+                .help("Sync pre-release tags (e.g. -rc, -beta) for images without an explicit release track"),
+        )
         .version("v0.2.0")
         .get_matches();
 
@@ -276,8 +617,69 @@ fn main() -> Result<()> {
         dry_run: matches.is_present("dry_run"),
         force: matches.is_present("force"),
         first_sync: matches.value_of("first_sync").unwrap().parse()?,
+        jobs: matches.value_of("jobs").unwrap().parse()?,
+        skip_errors: matches.is_present("skip_errors"),
+        report_only: matches.is_present("report_only"),
+        include_prerelease: matches.is_present("include_prerelease"),
     };
     sync_manifest(&directory, &manifest, &options)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config_with(exclude: Vec<&str>) -> ImageConfig {
+        ImageConfig {
+            image: String::from("foo/bar"),
+            include: None,
+            exclude: exclude.into_iter().map(String::from).collect(),
+            first_sync: None,
+            track: None,
+        }
+    }
+
+    #[test]
+    fn test_tag_matcher_applies_default_bans_on_stable_track() {
+        let config = config_with(vec![]);
+        let matcher = TagMatcher::from_config(&config, ReleaseTrack::Stable).unwrap();
+
+        assert!(!matcher.is_allowed("latest"));
+        assert!(!matcher.is_allowed("1.0.0-rc1"));
+        assert!(matcher.is_allowed("1.0.0"));
+    }
+
+    #[test]
+    fn test_tag_matcher_relaxes_default_rc_ban_on_prerelease_track() {
+        let config = config_with(vec![]);
+        let matcher = TagMatcher::from_config(&config, ReleaseTrack::Prerelease).unwrap();
+
+        assert!(matcher.is_allowed("1.0.0-rc1"));
+        // Other defaults (e.g. "latest") still apply.
+        assert!(!matcher.is_allowed("latest"));
+    }
+
+    #[test]
+    fn test_tag_matcher_honors_explicit_exclude_regardless_of_track() {
+        let config = config_with(vec!["^nightly"]);
+        let matcher = TagMatcher::from_config(&config, ReleaseTrack::Prerelease).unwrap();
+
+        assert!(!matcher.is_allowed("nightly-2024"));
+        // An explicit exclude list replaces the default bans entirely, so "rc" is
+        // no longer banned by default.
+        assert!(matcher.is_allowed("1.0.0-rc1"));
+    }
+
+    #[test]
+    fn test_display_duration_collapses_to_largest_unit() {
+        assert_eq!(chrono::Duration::seconds(45).to_display(), "45 Seconds");
+        assert_eq!(chrono::Duration::minutes(1).to_display(), "1 Minute");
+        assert_eq!(chrono::Duration::hours(2).to_display(), "2 Hours");
+        assert_eq!(chrono::Duration::days(1).to_display(), "1 Day");
+        assert_eq!(chrono::Duration::days(3).to_display(), "3 Days");
+        assert_eq!(chrono::Duration::days(365).to_display(), "1 Year");
+        assert_eq!(chrono::Duration::days(730).to_display(), "2 Years");
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::{anyhow, Context, Result};
+use std::process::{Command, ExitStatus};
+use std::thread;
+use std::time::Duration;
+
+/// Exit codes that indicate a command can never succeed by being re-run as-is
+/// (a missing binary, a non-executable file) rather than a transient hiccup
+/// (network blip, registry 5xx, out-of-space). Retrying these would just waste
+/// the backoff delay before surfacing the same terminal error.
+const NON_RETRYABLE_EXIT_CODES: &[i32] = &[126, 127];
+
+/// Default retryability check: everything is retryable except the small set of
+/// exit codes that can never succeed on retry.
+fn is_transient_failure(status: &ExitStatus) -> bool {
+    !matches!(status.code(), Some(code) if NON_RETRYABLE_EXIT_CODES.contains(&code))
+}
+
+/// Governs how many times a flaky external command is retried, how long to wait
+/// between attempts, and which failures are worth retrying at all.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub is_retryable: fn(&ExitStatus) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_secs(2),
+            is_retryable: is_transient_failure,
+        }
+    }
+}
+
+/// Spawns `command` and waits for it to exit, without inspecting the exit status.
+/// Only I/O errors from spawning the process are surfaced here.
+fn try_run_silent(command: &mut Command) -> Result<ExitStatus> {
+    command
+        .status()
+        .with_context(|| format!("Failed to spawn `{:?}`", command))
+}
+
+/// Runs `command`, retrying on failure up to `policy.attempts` times with
+/// exponential backoff between attempts — but only while `policy.is_retryable`
+/// says the failure is worth retrying; a non-retryable exit gives up immediately.
+/// Returns an error carrying the failing command string and exit code once
+/// attempts are exhausted or a non-retryable failure is hit.
+pub fn run(command: &mut Command, policy: &RetryPolicy) -> Result<()> {
+    let mut last_status = None;
+    let mut attempts_made = 0;
+
+    for attempt in 0..policy.attempts {
+        if attempt > 0 {
+            let delay = policy.base_delay * 2u32.pow(attempt - 1);
+            thread::sleep(delay);
+        }
+
+        let status = try_run_silent(command)?;
+        attempts_made += 1;
+        if status.success() {
+            return Ok(());
+        }
+
+        let retryable = (policy.is_retryable)(&status);
+        last_status = Some(status);
+        if !retryable {
+            break;
+        }
+    }
+
+    Err(anyhow!(
+        "command failed after {} attempt(s): `{:?}` (exit code: {:?})",
+        attempts_made,
+        command,
+        last_status.and_then(|status| status.code())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn fast_policy(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn test_run_succeeds_without_retrying() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exit 0");
+
+        assert!(run(&mut command, &fast_policy(3)).is_ok());
+    }
+
+    #[test]
+    fn test_run_gives_up_immediately_on_non_retryable_exit() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exit 127");
+
+        let policy = RetryPolicy {
+            attempts: 5,
+            base_delay: Duration::from_secs(5),
+            ..RetryPolicy::default()
+        };
+
+        let start = Instant::now();
+        let result = run(&mut command, &policy);
+
+        assert!(result.is_err());
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("exit code: Some(127)"));
+        // A non-retryable exit must not pay any backoff delay.
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_run_retries_transient_failures_until_attempts_exhausted() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exit 1");
+
+        let result = run(&mut command, &fast_policy(3));
+
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("3 attempt(s)"));
+    }
+
+    #[test]
+    fn test_run_retries_then_succeeds_once_transient_condition_clears() {
+        let marker = std::env::temp_dir().join(format!(
+            "singularity-sync-test-runner-retry-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&marker);
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!(
+            "test -f {0} && exit 0 || {{ touch {0}; exit 1; }}",
+            marker.display()
+        ));
+
+        let result = run(&mut command, &fast_policy(3));
+
+        assert!(result.is_ok());
+        let _ = std::fs::remove_file(&marker);
+    }
+}